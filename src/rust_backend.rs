@@ -1,6 +1,9 @@
 use crate::parser::Expr;
+use crate::parser::Redirect;
+use crate::parser::RedirectOp;
 use crate::parser::Stmt;
-use crate::parser::Stmt::{CallCmd, DefCmd, DefFun, DefVar, Return};
+use crate::parser::Stmt::{Block, CallCmd, DefCmd, DefFun, DefVar, For, If, Return, TestBlock, While};
+use crate::parser::BinOp;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
@@ -9,6 +12,8 @@ use std::collections::HashSet;
 
 const INDENT: isize = 4;
 
+const POSIX_BUILTINS: &[&str] = &["cd", "export", "unset", "pwd", "exit", "read"];
+
 macro_rules! output {
     ($fs: expr, $indent: expr, $($arg:tt)*) => {
         write!($fs, "{}", (0..$indent).map(|_| " ").collect::<String>()).expect("write error");
@@ -16,7 +21,7 @@ macro_rules! output {
     }
 }
 
-pub fn gen_code(stmts: &Vec<Stmt>, sym_table: &HashSet<&String>, file: &str) {
+pub fn gen_code(stmts: &Vec<Stmt>, sym_table: &HashSet<&String>, file: &str, gen_cli: bool, gen_tests: bool) {
     eprintln!("Generating rust code to {} ...", file);
     let mut file = fs::OpenOptions::new()
         .create(true)
@@ -31,28 +36,140 @@ pub fn gen_code(stmts: &Vec<Stmt>, sym_table: &HashSet<&String>, file: &str) {
     output!(file, 0, "use crate::cmd_lib::{{CmdResult, FunResult}};");
     output!(file, 0, "");
 
+    let fun_table: HashSet<&String> = stmts
+        .iter()
+        .filter_map(|s| if let DefFun(name, _, _) = s { Some(name) } else { None })
+        .collect();
+
+    let mut cli_cmds: Vec<(&String, &Vec<String>)> = Vec::new();
+    let mut test_idx = 0;
     for stmt in stmts {
         match stmt {
             DefFun(fun_name, parameters, body) => {
-                visit_def_fun(sym_table, &mut file, &fun_name, &parameters, &body)
+                visit_def_fun(sym_table, &fun_table, &mut file, &fun_name, &parameters, &body)
             }
             DefCmd(cmd_name, parameters, body) => {
-                visit_def_cmd(sym_table, &mut file, &cmd_name, &parameters, &body)
+                visit_def_cmd(sym_table, &fun_table, &mut file, &cmd_name, &parameters, &body);
+                cli_cmds.push((cmd_name, parameters));
+            }
+            TestBlock(annotations, body) => {
+                if gen_tests {
+                    visit_test_block(sym_table, &fun_table, &mut file, test_idx, &annotations, &body);
+                    test_idx += 1;
+                }
             }
             _ => eprintln!("Not supported yet!"),
         }
     }
+
+    if gen_cli {
+        visit_cli_dispatch(&mut file, &cli_cmds);
+    }
 }
 
-fn visit_def_var(file: &mut File, indent: &mut isize, var_name: &str, var_def: &Option<Expr>) {
-    if let Some(expr) = var_def {
-        output!(file, *indent, "let {} = {};", var_name, visit_expr(expr));
-    } else {
-        output!(file, *indent, "let {} = String::new();", var_name);
+fn visit_cli_dispatch(file: &mut File, cmds: &Vec<(&String, &Vec<String>)>) {
+    output!(file, 0, "fn main() {{");
+    output!(file, INDENT, "let args: Vec<String> = std::env::args().collect();");
+    output!(file, INDENT, "if args.len() < 2 {{");
+    output!(file, INDENT * 2, "print_help(&args[0]);");
+    output!(file, INDENT * 2, "std::process::exit(1);");
+    output!(file, INDENT, "}}");
+    output!(file, INDENT, "let cmd_name = args[1].as_str();");
+    output!(file, INDENT, "let cmd_args = &args[2..];");
+    output!(file, INDENT, "let result = match cmd_name {{");
+    output!(file, INDENT * 2, "\"help\" | \"--help\" => {{ print_help(&args[0]); return; }}");
+    for (cmd_name, parameters) in cmds {
+        output!(file, INDENT * 2, "\"{}\" => {{", cmd_name);
+        output!(
+            file,
+            INDENT * 3,
+            "if cmd_args.len() != {} {{",
+            parameters.len()
+        );
+        output!(
+            file,
+            INDENT * 4,
+            "eprintln!(\"{}: expected {} argument(s), got {{}}\", cmd_args.len());",
+            cmd_name,
+            parameters.len()
+        );
+        output!(file, INDENT * 4, "std::process::exit(1);");
+        output!(file, INDENT * 3, "}}");
+        let mut call_args = String::new();
+        for i in 0..parameters.len() {
+            if i != 0 {
+                call_args += ", ";
+            }
+            call_args += format!("cmd_args[{}].as_str()", i).as_ref();
+        }
+        output!(file, INDENT * 3, "{}({})", cmd_name, call_args);
+        output!(file, INDENT * 2, "}}");
+    }
+    output!(file, INDENT * 2, "other => {{");
+    output!(file, INDENT * 3, "eprintln!(\"unknown command: {{}}\", other);");
+    output!(file, INDENT * 3, "print_help(&args[0]);");
+    output!(file, INDENT * 3, "std::process::exit(1);");
+    output!(file, INDENT * 2, "}}");
+    output!(file, INDENT, "}};");
+    output!(file, INDENT, "if let Err(e) = result {{");
+    output!(file, INDENT * 2, "eprintln!(\"{{}}: {{}}\", cmd_name, e);");
+    output!(file, INDENT * 2, "std::process::exit(1);");
+    output!(file, INDENT, "}}");
+    output!(file, 0, "}}");
+    output!(file, 0, "");
+
+    output!(file, 0, "fn print_help(prog: &str) {{");
+    output!(file, INDENT, "eprintln!(\"usage: {{}} <command> [args...]\", prog);");
+    output!(file, INDENT, "eprintln!(\"commands:\");");
+    for (cmd_name, parameters) in cmds {
+        output!(
+            file,
+            INDENT,
+            "eprintln!(\"  {} {}\");",
+            cmd_name,
+            parameters.join(" ")
+        );
+    }
+    output!(file, 0, "}}");
+    output!(file, 0, "");
+}
+
+fn visit_def_var(sym_table: &HashSet<&String>, fun_table: &HashSet<&String>, file: &mut File, indent: &mut isize, var_name: &str, var_def: &Option<Expr>) {
+    match var_def {
+        Some(Expr::CallCmd(cmd, args)) => {
+            // A DefCmd returns CmdResult (unit), so it can't be captured directly; fall back to run_fun! like an unknown command.
+            let call = if fun_table.contains(cmd) {
+                if args.len() == 0 {
+                    format!("{}()", cmd)
+                } else {
+                    format!("{}({})", cmd, visit_call(args))
+                }
+            } else {
+                if args.len() == 0 {
+                    format!("run_fun!(\"{}\")", cmd)
+                } else {
+                    format!("run_fun!(\"{} {}\")", cmd, visit_call(args))
+                }
+            };
+            output!(file, *indent, "let {} = {}?;", var_name, call);
+        }
+        Some(expr) => output!(file, *indent, "let {} = {};", var_name, visit_expr(expr)),
+        None => output!(file, *indent, "let {} = String::new();", var_name),
     }
 }
 
-fn visit_def_fun(sym_table: &HashSet<&String>, file: &mut File, fun_name: &str, parameters: &Vec<String>, body: &Vec<Stmt>) {
+fn stmt_has_background(stmts: &Vec<Stmt>) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        CallCmd(cmd, _, _, _, background) => *background || cmd == "wait",
+        If(_, then_body, else_body) => {
+            stmt_has_background(then_body) || else_body.as_ref().map_or(false, stmt_has_background)
+        }
+        While(_, body) | For(_, _, body) | Block(body) => stmt_has_background(body),
+        _ => false,
+    })
+}
+
+fn visit_def_fun(sym_table: &HashSet<&String>, fun_table: &HashSet<&String>, file: &mut File, fun_name: &str, parameters: &Vec<String>, body: &Vec<Stmt>) {
     let mut indent = 0;
     let mut fun_args = String::new();
 
@@ -67,15 +184,24 @@ fn visit_def_fun(sym_table: &HashSet<&String>, file: &mut File, fun_name: &str,
     output!(file, 0, "{}", fun_args);
 
     indent += INDENT;
+    let ok_expr = "Ok(String::new())";
+    let has_bg = stmt_has_background(body);
+    if has_bg {
+        output!(file, indent, "let mut __bg_handles = Vec::new();");
+    }
     for (i, stmt) in body.iter().enumerate() {
-        visit_stmt(sym_table, file, &mut indent, stmt, i == body.len()-1);
+        visit_stmt(sym_table, fun_table, file, &mut indent, ok_expr, stmt, !has_bg && i == body.len()-1);
+    }
+    if has_bg {
+        join_outstanding(file, indent);
+        output!(file, indent, "{}", ok_expr);
     }
 
     output!(file, 0, "}}");
     output!(file, 0, "");
 }
 
-fn visit_def_cmd(sym_table: &HashSet<&String>, file: &mut File, fun_name: &str, parameters: &Vec<String>, body: &Vec<Stmt>) {
+fn visit_def_cmd(sym_table: &HashSet<&String>, fun_table: &HashSet<&String>, file: &mut File, fun_name: &str, parameters: &Vec<String>, body: &Vec<Stmt>) {
     let mut indent = 0;
     let mut fun_args = String::new();
 
@@ -90,19 +216,133 @@ fn visit_def_cmd(sym_table: &HashSet<&String>, file: &mut File, fun_name: &str,
     output!(file, 0, "{}", fun_args);
 
     indent += INDENT;
+    let ok_expr = "Ok(())";
+    let has_bg = stmt_has_background(body);
+    if has_bg {
+        output!(file, indent, "let mut __bg_handles = Vec::new();");
+    }
+    for (i, stmt) in body.iter().enumerate() {
+        visit_stmt(sym_table, fun_table, file, &mut indent, ok_expr, stmt, !has_bg && i == body.len()-1);
+    }
+    if has_bg {
+        join_outstanding(file, indent);
+        output!(file, indent, "{}", ok_expr);
+    }
+
+    output!(file, 0, "}}");
+    output!(file, 0, "");
+}
+
+// Handles pushed onto `__bg_handles` by a backgrounded call (and drained by `wait`) live in a function-top-level
+// Vec rather than per-call `let` bindings, so draining them here works regardless of how deeply the
+// originating call was nested in `if`/`while`/`for`/`Block`.
+fn join_outstanding(file: &mut File, indent: isize) {
+    output!(file, indent, "for __h in __bg_handles.drain(..) {{");
+    output!(file, indent + INDENT, "let _ = __h.wait();");
+    output!(file, indent, "}}");
+}
+
+fn visit_test_block(sym_table: &HashSet<&String>, fun_table: &HashSet<&String>, file: &mut File, idx: usize, annotations: &Vec<String>, body: &Vec<Stmt>) {
+    let should_fail = annotations.iter().any(|a| a == "should_fail");
+    let ignore = annotations.iter().any(|a| a == "ignore");
+
+    output!(file, 0, "#[test]");
+    if ignore {
+        output!(file, 0, "#[ignore]");
+    }
+    output!(file, 0, "fn generated_test_{}() {{", idx);
+
+    let mut indent = INDENT;
+    output!(file, indent, "let result = (|| -> CmdResult {{");
+    indent += INDENT;
+    let ok_expr = "Ok(())";
+    let has_bg = stmt_has_background(body);
+    if has_bg {
+        output!(file, indent, "let mut __bg_handles = Vec::new();");
+    }
     for (i, stmt) in body.iter().enumerate() {
-        visit_stmt(sym_table, file, &mut indent, stmt, i == body.len()-1);
+        visit_stmt(sym_table, fun_table, file, &mut indent, ok_expr, stmt, !has_bg && i == body.len() - 1);
+    }
+    if has_bg {
+        join_outstanding(file, indent);
+        output!(file, indent, "{}", ok_expr);
+    }
+    indent -= INDENT;
+    output!(file, indent, "}})();");
+
+    if should_fail {
+        output!(file, indent, "assert!(result.is_err());");
+    } else {
+        output!(file, indent, "result.unwrap();");
     }
 
     output!(file, 0, "}}");
     output!(file, 0, "");
 }
 
-fn visit_stmt(sym_table: &HashSet<&String>, file: &mut File, indent: &mut isize, stmt: &Stmt, is_last: bool) {
+fn visit_stmt(sym_table: &HashSet<&String>, fun_table: &HashSet<&String>, file: &mut File, indent: &mut isize, ok_expr: &str, stmt: &Stmt, is_last: bool) {
     match stmt {
-        CallCmd(cmd, parameters) => visit_call_cmd(sym_table, file, indent, &cmd, &parameters, is_last),
+        CallCmd(cmd, parameters, pipeline, redirect, background) => {
+            visit_call_cmd(sym_table, file, indent, ok_expr, &cmd, &parameters, &pipeline, &redirect, *background, is_last)
+        }
         Return(expr) => visit_return(file, indent, &expr),
-        DefVar(var_name, var_def) => visit_def_var(file, indent, &var_name, &var_def),
+        DefVar(var_name, var_def) => visit_def_var(sym_table, fun_table, file, indent, &var_name, &var_def),
+        If(cond, then_body, else_body) => {
+            output!(file, *indent, "if {} {{", visit_expr(cond));
+            *indent += INDENT;
+            // then_body is only tail-valued when there's a matching else arm of the same type;
+            // otherwise (like While/For) the if is a bare statement and ok_expr is emitted after it.
+            let then_is_last = is_last && else_body.is_some();
+            for (i, s) in then_body.iter().enumerate() {
+                visit_stmt(sym_table, fun_table, file, indent, ok_expr, s, then_is_last && i == then_body.len() - 1);
+            }
+            *indent -= INDENT;
+            if let Some(else_body) = else_body {
+                output!(file, *indent, "}} else {{");
+                *indent += INDENT;
+                for (i, s) in else_body.iter().enumerate() {
+                    visit_stmt(sym_table, fun_table, file, indent, ok_expr, s, is_last && i == else_body.len() - 1);
+                }
+                *indent -= INDENT;
+            }
+            output!(file, *indent, "}}");
+            if is_last && else_body.is_none() {
+                output!(file, *indent, "{}", ok_expr);
+            }
+        }
+        While(cond, body) => {
+            output!(file, *indent, "while {} {{", visit_expr(cond));
+            *indent += INDENT;
+            for s in body {
+                visit_stmt(sym_table, fun_table, file, indent, ok_expr, s, false);
+            }
+            *indent -= INDENT;
+            output!(file, *indent, "}}");
+            if is_last {
+                output!(file, *indent, "{}", ok_expr);
+            }
+        }
+        For(var_name, iter_expr, body) => {
+            output!(file, *indent, "for {} in {} {{", var_name, visit_expr(iter_expr));
+            *indent += INDENT;
+            for s in body {
+                visit_stmt(sym_table, fun_table, file, indent, ok_expr, s, false);
+            }
+            *indent -= INDENT;
+            output!(file, *indent, "}}");
+            if is_last {
+                output!(file, *indent, "{}", ok_expr);
+            }
+        }
+        Block(body) => {
+            output!(file, *indent, "{{");
+            *indent += INDENT;
+            for (i, s) in body.iter().enumerate() {
+                visit_stmt(sym_table, fun_table, file, indent, ok_expr, s, is_last && i == body.len() - 1);
+            }
+            *indent -= INDENT;
+            output!(file, *indent, "}}");
+        }
         _ => {
             let mut stmt = format!("{:?}", stmt);
             if !is_last {
@@ -113,7 +353,31 @@ fn visit_stmt(sym_table: &HashSet<&String>, file: &mut File, indent: &mut isize,
     }
 }
 
-fn visit_call_cmd(sym_table: &HashSet<&String>, file: &mut File, indent: &mut isize, cmd: &str, parameters: &Vec<Expr>, is_last: bool) {
+fn visit_call_cmd(
+    sym_table: &HashSet<&String>,
+    file: &mut File,
+    indent: &mut isize,
+    ok_expr: &str,
+    cmd: &str,
+    parameters: &Vec<Expr>,
+    pipeline: &Vec<(String, Vec<Expr>)>,
+    redirect: &Option<Redirect>,
+    background: bool,
+    is_last: bool,
+) {
+    if (cmd == "wait" || POSIX_BUILTINS.contains(&cmd)) && (!pipeline.is_empty() || redirect.is_some()) {
+        panic!("{}: piping into or redirecting a builtin is not supported", cmd);
+    }
+
+    if cmd == "wait" {
+        join_outstanding(file, *indent);
+        return;
+    }
+
+    if visit_posix_builtin(file, indent, cmd, parameters, ok_expr, is_last) {
+        return;
+    }
+
     let mut cmd = String::from(cmd);
     let mut ending = String::new();
     let mut builtin = false;
@@ -131,20 +395,130 @@ fn visit_call_cmd(sym_table: &HashSet<&String>, file: &mut File, indent: &mut is
     }
 
     if builtin || sym_table.contains(&cmd) {
+        if !pipeline.is_empty() || redirect.is_some() {
+            panic!(
+                "{}: piping into or redirecting a generated function/builtin call is not supported",
+                cmd
+            );
+        }
         if parameters.len() == 0 {
             output!(file, *indent, "{}(){}", cmd, ending);
         } else {
             output!(file, *indent, "{}({}){}", cmd, visit_call(parameters), ending);
         }
+        return;
+    }
+
+    let mut stages = String::new();
+    if parameters.len() == 0 {
+        stages += cmd.as_ref();
     } else {
-        if parameters.len() == 0 {
-            output!(file, *indent, "run_cmd!(\"{}\"){}", cmd, ending);
-        } else {
-            output!(file, *indent, "run_cmd!(\"{} {}\"){}", cmd, visit_call(parameters), ending);
+        stages += format!("{} {}", cmd, visit_call(parameters)).as_ref();
+    }
+    for (stage_cmd, stage_params) in pipeline {
+        stages += " | ";
+        stages += stage_cmd.as_ref();
+        if stage_params.len() != 0 {
+            stages += " ";
+            stages += visit_call(stage_params).as_ref();
         }
     }
+    if let Some(redirect) = redirect {
+        stages += match redirect.op {
+            RedirectOp::Write => " > ",
+            RedirectOp::Append => " >> ",
+            RedirectOp::Read => " < ",
+        };
+        stages += visit_call(&vec![redirect.target.clone()]).as_ref();
+    }
+
+    if background {
+        output!(file, *indent, "__bg_handles.push(spawn!(\"{}\")?);", stages);
+    } else {
+        output!(file, *indent, "run_cmd!(\"{}\"){}", stages, ending);
+    }
 }
     
+fn visit_rust_value(expr: &Expr) -> String {
+    match expr {
+        Expr::LitStr(s) => format!("\"{}\"", s),
+        Expr::Var(v) => v.identifier.clone(),
+        _ => visit_expr(expr),
+    }
+}
+
+// Builtins never produce a value typed as the enclosing CmdResult/FunResult themselves, so
+// (unlike the ordinary run_cmd! path) they can't serve as a function's tail expression.
+// Each arm always terminates its own statement(s), and ok_expr is emitted separately afterward
+// when is_last, the same way While/For do; `exit` diverges, so it returns before that and gets none.
+fn visit_posix_builtin(file: &mut File, indent: &mut isize, cmd: &str, parameters: &Vec<Expr>, ok_expr: &str, is_last: bool) -> bool {
+    match cmd {
+        "cd" => match parameters.len() {
+            0 => {
+                output!(file, *indent, "std::env::set_current_dir(std::env::var(\"HOME\").unwrap_or_default())?;");
+            }
+            1 => {
+                output!(file, *indent, "std::env::set_current_dir({})?;", visit_rust_value(&parameters[0]));
+            }
+            n => panic!("cd: expected 0 or 1 arguments, got {}", n),
+        },
+        "export" => match parameters.len() {
+            1 => {
+                let name = match &parameters[0] {
+                    Expr::LitStr(s) => s.clone(),
+                    Expr::Var(v) => v.identifier.clone(),
+                    other => visit_rust_value(other),
+                };
+                output!(file, *indent, "std::env::set_var(\"{}\", {});", name, name);
+            }
+            2 => {
+                output!(
+                    file,
+                    *indent,
+                    "std::env::set_var({}, {});",
+                    visit_rust_value(&parameters[0]),
+                    visit_rust_value(&parameters[1])
+                );
+            }
+            n => panic!("export: expected 1 or 2 arguments, got {}", n),
+        },
+        "unset" => {
+            for param in parameters {
+                output!(file, *indent, "std::env::remove_var({});", visit_rust_value(param));
+            }
+        }
+        "pwd" => {
+            output!(
+                file,
+                *indent,
+                "println!(\"{{}}\", std::env::current_dir().map(|p| p.display().to_string()).unwrap_or_default());"
+            );
+        }
+        "exit" => {
+            let code = if parameters.len() == 0 { "0".to_string() } else { visit_rust_value(&parameters[0]) };
+            output!(file, *indent, "std::process::exit({});", code);
+            return true;
+        }
+        "read" => {
+            let var = match parameters.get(0) {
+                Some(Expr::Var(v)) => v.identifier.clone(),
+                Some(other) => visit_rust_value(other),
+                None => "REPLY".to_string(),
+            };
+            output!(file, *indent, "let mut {} = String::new();", var);
+            output!(file, *indent, "std::io::stdin().read_line(&mut {})?;", var);
+            output!(file, *indent, "let {} = {}.trim_end().to_string();", var, var);
+        }
+        _ => return false,
+    }
+
+    if is_last {
+        output!(file, *indent, "{}", ok_expr);
+    }
+
+    true
+}
+
 fn visit_call(parameters: &Vec<Expr>) -> String {
     let mut args = String::new();
     for (i, expr) in parameters.iter().enumerate() {
@@ -157,21 +531,72 @@ fn visit_call(parameters: &Vec<Expr>) -> String {
 }
 
 fn visit_return(file: &mut File, indent: &mut isize, expr: &Expr) {
-    output!(file, *indent, "return {}", visit_expr(expr));
-}
-
-fn visit_expr(expr: &Expr) -> String {
-    match expr {
+    let rust_expr = match expr {
         Expr::LitNum(n) => {
             if *n == 0 {
                 format!("Ok(())")
             } else {
                 format!("Err(())")
             }
-        },
+        }
+        _ => visit_expr(expr),
+    };
+    output!(file, *indent, "return {};", rust_expr);
+}
+
+fn bin_op_str(op: &BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Mod => "%",
+        BinOp::Eq => "==",
+        BinOp::Ne => "!=",
+        BinOp::Lt => "<",
+        BinOp::Gt => ">",
+        BinOp::Le => "<=",
+        BinOp::Ge => ">=",
+        BinOp::And => "&&",
+        BinOp::Or => "||",
+    }
+}
+
+fn bin_op_prec(op: &BinOp) -> u8 {
+    match op {
+        BinOp::Mul | BinOp::Div | BinOp::Mod => 4,
+        BinOp::Add | BinOp::Sub => 3,
+        BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => 2,
+        BinOp::And => 1,
+        BinOp::Or => 0,
+    }
+}
+
+fn visit_expr_prec(expr: &Expr) -> (String, u8) {
+    match expr {
+        Expr::BinOp(lhs, op, rhs) => {
+            let (lhs_str, lhs_prec) = visit_expr_prec(lhs);
+            let (rhs_str, rhs_prec) = visit_expr_prec(rhs);
+            let op_prec = bin_op_prec(op);
+            let lhs_str = if lhs_prec < op_prec { format!("({})", lhs_str) } else { lhs_str };
+            let rhs_str = if rhs_prec <= op_prec { format!("({})", rhs_str) } else { rhs_str };
+            (format!("{} {} {}", lhs_str, bin_op_str(op), rhs_str), op_prec)
+        }
+        Expr::Not(e) => {
+            let (s, _) = visit_expr_prec(e);
+            (format!("!({})", s), u8::MAX)
+        }
+        _ => (visit_expr(expr), u8::MAX),
+    }
+}
+
+fn visit_expr(expr: &Expr) -> String {
+    match expr {
+        Expr::LitNum(n) => format!("{}", n),
         Expr::LitStr(s) => format!("\"{}\"", s),
         Expr::Var(v) => format!("\"${{{}}}\"", v.identifier),
-        Expr::CallFun(f, args) => format!("{}({})?;", f, visit_call(args)),
+        Expr::CallFun(f, args) => format!("{}({})?", f, visit_call(args)),
+        Expr::BinOp(..) | Expr::Not(..) => visit_expr_prec(expr).0,
         _ => format!("{:?}", expr),
     }
 }
@@ -213,3 +638,98 @@ fn format_str(input: &str) -> String {
 fn test_format_str() {
     assert_eq!(format_str("${a} aa ${b} bb ${cc}"), "{} aa {} bb {}, a, b, cc".to_string());
 }
+
+#[test]
+fn test_bin_op_prec_orders_arithmetic_above_comparison_above_logic() {
+    assert!(bin_op_prec(&BinOp::Mul) > bin_op_prec(&BinOp::Add));
+    assert!(bin_op_prec(&BinOp::Add) > bin_op_prec(&BinOp::Eq));
+    assert!(bin_op_prec(&BinOp::Eq) > bin_op_prec(&BinOp::And));
+    assert!(bin_op_prec(&BinOp::And) > bin_op_prec(&BinOp::Or));
+}
+
+#[test]
+fn test_visit_expr_parenthesizes_lower_precedence_child() {
+    // (1 + 2) * 3
+    let expr = Expr::BinOp(
+        Box::new(Expr::BinOp(Box::new(Expr::LitNum(1)), BinOp::Add, Box::new(Expr::LitNum(2)))),
+        BinOp::Mul,
+        Box::new(Expr::LitNum(3)),
+    );
+    assert_eq!(visit_expr(&expr), "(1 + 2) * 3");
+}
+
+fn gen_code_for_builtin(cmd: &str, parameters: &Vec<Expr>, is_last: bool) -> (bool, String) {
+    let path = format!("/tmp/rust_shell_script_test_builtin_{}.rs", cmd);
+    let mut file = fs::OpenOptions::new().create(true).write(true).truncate(true).open(&path).expect("open temp file");
+    let mut indent = 0;
+    let handled = visit_posix_builtin(&mut file, &mut indent, cmd, parameters, "Ok(())", is_last);
+    drop(file);
+    let contents = fs::read_to_string(&path).expect("read temp file");
+    fs::remove_file(&path).expect("remove temp file");
+    (handled, contents)
+}
+
+#[test]
+fn test_visit_posix_builtin_cd_defaults_to_home_with_no_args() {
+    let (handled, contents) = gen_code_for_builtin("cd", &vec![], true);
+    assert!(handled);
+    assert!(contents.contains("std::env::set_current_dir(std::env::var(\"HOME\").unwrap_or_default())"));
+}
+
+#[test]
+fn test_visit_posix_builtin_emits_ok_expr_in_tail_position() {
+    let (handled, contents) = gen_code_for_builtin("cd", &vec![], true);
+    assert!(handled);
+    assert!(contents.trim_end().ends_with("Ok(())"));
+}
+
+#[test]
+fn test_visit_posix_builtin_omits_ok_expr_when_not_last() {
+    let (handled, contents) = gen_code_for_builtin("cd", &vec![], false);
+    assert!(handled);
+    assert!(!contents.contains("Ok(())"));
+}
+
+#[test]
+#[should_panic(expected = "cd: expected 0 or 1 arguments, got 2")]
+fn test_visit_posix_builtin_cd_rejects_extra_args() {
+    gen_code_for_builtin("cd", &vec![Expr::LitStr("a".to_string()), Expr::LitStr("b".to_string())], true);
+}
+
+#[test]
+fn test_visit_posix_builtin_export_with_one_arg_exports_the_existing_variable() {
+    let (handled, contents) = gen_code_for_builtin("export", &vec![Expr::LitStr("FOO".to_string())], true);
+    assert!(handled);
+    assert!(contents.contains("std::env::set_var(\"FOO\", FOO);"));
+}
+
+#[test]
+fn test_visit_posix_builtin_unset_accepts_zero_args() {
+    let (handled, contents) = gen_code_for_builtin("unset", &vec![], true);
+    assert!(handled);
+    assert_eq!(contents, "Ok(())\n");
+}
+
+#[test]
+fn test_visit_posix_builtin_read_defaults_to_reply() {
+    let (handled, contents) = gen_code_for_builtin("read", &vec![], true);
+    assert!(handled);
+    assert!(contents.contains("let mut REPLY = String::new();"));
+}
+
+#[test]
+fn test_visit_posix_builtin_unknown_is_not_handled() {
+    let (handled, _) = gen_code_for_builtin("not_a_builtin", &vec![], true);
+    assert!(!handled);
+}
+
+#[test]
+fn test_visit_expr_omits_parens_for_left_associative_chain() {
+    // 1 - 2 - 3
+    let expr = Expr::BinOp(
+        Box::new(Expr::BinOp(Box::new(Expr::LitNum(1)), BinOp::Sub, Box::new(Expr::LitNum(2)))),
+        BinOp::Sub,
+        Box::new(Expr::LitNum(3)),
+    );
+    assert_eq!(visit_expr(&expr), "1 - 2 - 3");
+}